@@ -14,11 +14,12 @@ fn main() {
 fn run() -> Result<()> {
     println!("Hello!");
 
-    let mut plugin_state = PluginState::new();
+    let config = load_plugin_config("plugins.toml")?;
+    let mut plugins = load_plugins_from_config(&config)?;
 
     let mut line = String::new();
     loop {
-        display_command_line_prompt(&plugin_state)?;
+        display_command_line_prompt(&plugins, &config)?;
 
         line.clear();
         std::io::stdin().read_line(&mut line)?;
@@ -32,14 +33,28 @@ fn run() -> Result<()> {
             continue;
         };
 
+        for &handle in &plugins {
+            // `CommandInput` is broadcast to every loaded plugin regardless
+            // of whether it's the one handling the command, so a cache-hit
+            // plugin that cares about it needs its module loaded now rather
+            // than waiting on a command invocation that may never come.
+            if let Err(e) = ensure_plugin_loaded(handle, &config.cache_dir) {
+                eprintln!("ERROR: failed to load plugin for event: {}", e.cause);
+                continue;
+            }
+            dispatch_plugin_event(handle, plugin_sdk::PluginEvent::CommandInput { line: plugin_sdk::FfiSafeStr::new(line.trim()) });
+        }
+
         match command {
-            "help" => do_command_help(&plugin_state),
+            "help" => do_command_help(&plugins),
             "echo" => do_command_echo(&args),
             "exit" => break,
-            "load-plugin" => do_command_load_plugin(&args, &mut plugin_state)?,
-            "unload-all-plugins" => do_command_unload_all_plugins(&mut plugin_state)?,
+            "load-plugin" => do_command_load_plugin(&args, &mut plugins, &config)?,
+            "plugin" => do_command_plugin(&args, &mut plugins, &config)?,
+            "unload-all-plugins" => do_command_unload_all_plugins(&mut plugins)?,
+            "reset-plugin" => do_command_reset_plugin(&plugins),
             _ => {
-                let handled = do_plugin_command(command, &args, &mut plugin_state)?;
+                let handled = do_plugin_command(command, &args, &plugins, &config)?;
 
                 if !handled {
                     println!("unknown command `{command}`");
@@ -53,31 +68,60 @@ fn run() -> Result<()> {
     Ok(())
 }
 
-fn display_command_line_prompt(plugin_state: &PluginState) -> Result<()> {
-    // SAFETY: static mut variable access is safe because we don't have any multithreading
-    if let Some(function) = plugin_state.custom_prompt_function {
-        // SAFETY: safety of this call depends on the implementation. If we
-        // don't control who writes this code and have no way of making sure it
-        // is safe, than we cannot guarantee that this call is safe.
-        unsafe { function() };
-    } else {
+fn display_command_line_prompt(plugins: &[plugin_sdk::Handle], config: &PluginConfig) -> Result<()> {
+    let mut fragments = Vec::new();
+    for &handle in plugins {
+        // Same reasoning as the `CommandInput` broadcast in `run`: `PromptTick`
+        // is ambient, not tied to a command invocation, so a cache-hit plugin
+        // would otherwise never get its module loaded and would silently drop
+        // out of the prompt forever.
+        if let Err(e) = ensure_plugin_loaded(handle, &config.cache_dir) {
+            eprintln!("ERROR: failed to load plugin for event: {}", e.cause);
+            continue;
+        }
+
+        let Some(result) = dispatch_plugin_event(handle, plugin_sdk::PluginEvent::PromptTick) else {
+            continue;
+        };
+
+        let message = result.message();
+        if !message.is_empty() {
+            fragments.push(message.to_owned());
+        }
+    }
+
+    if fragments.is_empty() {
         print!("> ");
-        std::io::stdout().flush()?;
+    } else {
+        print!("{} ", fragments.join(&config.prompt_separator));
     }
+    std::io::stdout().flush()?;
 
     Ok(())
 }
 
-fn do_command_help(plugin_state: &PluginState) {
+fn do_command_help(plugins: &[plugin_sdk::Handle]) {
     println!("supported commands:");
     println!("   help");
     println!("   echo");
     println!("   exit");
     println!("   load-plugin");
+    println!("   plugin add <path>");
+    println!("   plugin rm <name>");
     println!("   unload-all-plugins");
-    if !plugin_state.custom_commands.is_empty() {
-        println!("commands from plugins:");
-        for command in plugin_state.custom_commands.keys() {
+    println!("   reset-plugin");
+
+    for &handle in plugins {
+        let Some(plugin) = loaded_plugins().get_mut(handle) else {
+            continue;
+        };
+
+        if plugin.cached_commands.is_empty() {
+            continue;
+        }
+
+        println!("commands from plugin '{}':", plugin.name);
+        for command in &plugin.cached_commands {
             println!("   {command}");
         }
     }
@@ -105,105 +149,345 @@ fn parse_command(s: &str) -> Option<(&str, Vec<&str>)> {
 // plugins
 ////////////////////////////////////////////////////////////////////////////////
 
-struct PluginState {
-    module: *mut libc::c_void,
-    path: Option<std::ffi::CString>,
-    custom_prompt_function: Option<plugin_sdk::CustomPromptFn>,
+/// A plugin module that has actually been `dlopen`ed: the handle we'll
+/// `dlclose`, the event entry point, and the per-plugin command namespace
+/// populated by `ffi_register_command` while handling `PluginEvent::Load`.
+struct LoadedModule {
+    dl_handle: *mut libc::c_void,
+    on_event: Option<plugin_sdk::PluginOnEventFn>,
     custom_commands: std::collections::HashMap<String, plugin_sdk::CommandHandler>,
 }
 
-impl PluginState {
-    fn new() -> Self {
-        Self {
-            module: std::ptr::null_mut(),
-            path: None,
-            custom_prompt_function: None,
-            custom_commands: Default::default(),
+/// A plugin known to the registry, which may or may not currently be
+/// `dlopen`ed. `cached_commands` is filled in from the on-disk cache (or, the
+/// first time a plugin is seen, from an eager load) and answers `help` and
+/// command-existence lookups on its own; `module` is `None` until one of the
+/// plugin's commands is actually invoked, at which point it's lazily loaded.
+struct LoadedPlugin {
+    name: String,
+    path: std::ffi::CString,
+    cached_commands: Vec<String>,
+    module: Option<LoadedModule>,
+}
+
+/// Plugin contexts are kept behind a [`plugin_sdk::HandleMap`] rather than
+/// handed to plugins as raw pointers: a stale or forged handle is rejected
+/// with a lookup failure instead of being dereferenced. Each loaded plugin
+/// gets its own handle and its own `custom_commands` namespace, so two
+/// plugins registering the same command name no longer collide.
+static mut LOADED_PLUGINS: Option<plugin_sdk::HandleMap<LoadedPlugin>> = None;
+
+fn loaded_plugins() -> &'static mut plugin_sdk::HandleMap<LoadedPlugin> {
+    // SAFETY: access is safe because we don't have any multithreading
+    unsafe { LOADED_PLUGINS.get_or_insert_with(plugin_sdk::HandleMap::new) }
+}
+
+/// Sends `event` to the plugin loaded under `handle`, if any. Returns the
+/// plugin's `FfiError` on success -- its `message` carries a return value for
+/// events like `PromptTick` that produce one -- and reports a failure result
+/// or a caught panic the same way [`do_plugin_command`] does, returning
+/// `None` for those. A no-op (returning `None`) if the plugin's module isn't
+/// currently loaded.
+fn dispatch_plugin_event(handle: plugin_sdk::Handle, event: plugin_sdk::PluginEvent) -> Option<plugin_sdk::FfiError> {
+    let plugin = loaded_plugins().get_mut(handle)?;
+    let on_event = plugin.module.as_ref()?.on_event?;
+
+    let event = &event as *const plugin_sdk::PluginEvent;
+    // SAFETY: safety of this call depends on the implementation. If we don't
+    // control who writes this code and have no way of making sure it is
+    // safe, than we cannot guarantee that this call is safe.
+    //
+    // `on_event` is `extern "C-unwind"`, so a panic inside plugin code is
+    // legally allowed to unwind back out to us here instead of aborting the
+    // process; we catch it rather than letting it propagate further. We
+    // report it ourselves below, so swap in a no-op hook just for this call
+    // to avoid a duplicate print from the default one -- restoring it right
+    // after means an unrelated panic on the host side still gets the normal
+    // message and location.
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_panic_info| {}));
+    let caught = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| unsafe { on_event(handle, event) }));
+    std::panic::set_hook(default_hook);
+
+    match caught {
+        Ok(e) if e.code == plugin_sdk::FfiError::CODE_SUCCESS => Some(e),
+        Ok(e) => {
+            let code = e.code;
+            let e = Error::new(e);
+            eprintln!("ERROR (code {code}) handling plugin event: {}", e.cause);
+            eprintln!("Backtrace:");
+            eprintln!("{}", e.backtrace);
+            None
+        }
+        Err(payload) => {
+            let message = plugin_sdk::panic_payload_message(&*payload);
+            let backtrace = std::backtrace::Backtrace::capture();
+            println!("PANIC handling plugin event: {message}");
+            println!("Backtrace:\n{backtrace}");
+            None
         }
     }
 }
 
-fn do_plugin_command(command: &str, args: &[&str], plugin_state: &mut PluginState) -> Result<bool> {
-    let Some(handler) = plugin_state.custom_commands.get(command) else {
-        return Ok(false);
-    };
+fn do_plugin_command(command: &str, args: &[&str], plugins: &[plugin_sdk::Handle], config: &PluginConfig) -> Result<bool> {
+    for &handle in plugins {
+        let Some(plugin) = loaded_plugins().get_mut(handle) else {
+            continue;
+        };
 
-    handler.call(args);
+        if !plugin.cached_commands.iter().any(|c| c == command) {
+            continue;
+        }
 
-    return Ok(true);
-}
+        let name = plugin.name.clone();
+        if let Err(e) = ensure_plugin_loaded(handle, &config.cache_dir) {
+            eprintln!("ERROR: failed to load plugin '{name}': {}", e.cause);
+            continue;
+        }
 
-fn do_command_load_plugin(args: &[&str], plugin_state: &mut PluginState) -> Result<()> {
-    if plugin_state.path.is_some() {
-        println!("plugin already loaded, multiple plugins are not supported yet");
-        return Ok(());
+        let Some(plugin) = loaded_plugins().get_mut(handle) else {
+            continue;
+        };
+        let Some(module) = plugin.module.as_ref() else {
+            continue;
+        };
+        let Some(handler) = module.custom_commands.get(command) else {
+            continue;
+        };
+
+        if let Err(e) = handler.call(args) {
+            let code = e.code;
+            let e = Error::new(e);
+            eprintln!("ERROR (code {code}): {}", e.cause);
+            eprintln!("Backtrace:");
+            eprintln!("{}", e.backtrace);
+        }
+
+        return Ok(true);
     }
 
+    Ok(false)
+}
+
+fn do_command_load_plugin(args: &[&str], plugins: &mut Vec<plugin_sdk::Handle>, config: &PluginConfig) -> Result<()> {
     let [path] = args else {
         println!("expected a file path as first argument");
         return Ok(());
     };
 
-    let path = std::ffi::CString::new(*path).map_err(Error::new)?;
+    add_plugin(path, plugins, config)
+}
 
-    // SAFETY: this is safe because file path is a valid nul-terminated string pointer
-    let module = unsafe { libc::dlopen(path.as_ptr(), libc::RTLD_LOCAL | libc::RTLD_NOW) };
-    if module.is_null() {
-        return Err(make_dlerror_error());
+fn do_command_plugin(args: &[&str], plugins: &mut Vec<plugin_sdk::Handle>, config: &PluginConfig) -> Result<()> {
+    match args {
+        ["add", path] => add_plugin(path, plugins, config),
+        ["rm", name] => remove_plugin(name, plugins, config),
+        _ => {
+            println!("usage: plugin add <path> | plugin rm <name>");
+            Ok(())
+        }
     }
+}
 
-    plugin_state.module = module;
-    plugin_state.path = Some(path);
+fn add_plugin(path: &str, plugins: &mut Vec<plugin_sdk::Handle>, config: &PluginConfig) -> Result<()> {
+    let name = match plugin_name(std::path::Path::new(path)) {
+        Ok(name) => name,
+        Err(e) => {
+            println!("ERROR: {}", e.cause);
+            return Ok(());
+        }
+    };
 
-    // SAFETY: assumming the plugin defines the symbol with the correct signature
-    let fn_ptr = unsafe { load_symbol(module, c"ffi_custom_prompt")? };
-    plugin_state.custom_prompt_function = Some(fn_ptr);
+    let existing = plugins.iter().copied().find(|&handle| {
+        loaded_plugins().get_mut(handle).is_some_and(|plugin| plugin.name == name)
+    });
+    if let Some(handle) = existing {
+        // The module stays mapped since we never `dlclose`d it, so tell it
+        // that it's being reloaded rather than treating this as a first load.
+        dispatch_plugin_event(handle, plugin_sdk::PluginEvent::Reload);
+        return Ok(());
+    }
 
-    // SAFETY: assumming the plugin defines the symbol with the correct signature
-    let register_commands: plugin_sdk::RegisterCommandsFn = unsafe {
-        load_symbol(module, c"ffi_register_commands")?
+    let handle = register_plugin(name, std::path::Path::new(path), &config.cache_dir)?;
+    plugins.push(handle);
+
+    Ok(())
+}
+
+fn remove_plugin(name: &str, plugins: &mut Vec<plugin_sdk::Handle>, config: &PluginConfig) -> Result<()> {
+    let Some(pos) = plugins.iter().position(|&handle| {
+        loaded_plugins().get_mut(handle).is_some_and(|plugin| plugin.name == name)
+    }) else {
+        println!("no plugin named '{name}' is loaded");
+        return Ok(());
     };
 
-    let context = plugin_state as *mut _ as *mut ();
-    unsafe { (register_commands)(context) }
+    let handle = plugins.remove(pos);
+    dispatch_plugin_event(handle, plugin_sdk::PluginEvent::Shutdown);
+
+    if let Some(plugin) = loaded_plugins().remove(handle) {
+        if let Some(mut module) = plugin.module {
+            // Each `CommandHandler`'s `Drop` calls back into the plugin's
+            // code, so every handler must be dropped before we `dlclose` and
+            // unmap that code -- not after, or `module`'s own drop at the end
+            // of this scope would use-after-free it.
+            module.custom_commands.clear();
+
+            // SAFETY: `module.dl_handle` was returned by a successful `dlopen`
+            let rc = unsafe { libc::dlclose(module.dl_handle) };
+            if rc != 0 {
+                eprintln!("ERROR: failed to unload plugin {:?}: {}", plugin.path, make_dlerror_error().cause);
+            }
+        }
+    }
+
+    if let Err(e) = remove_plugin_cache(&config.cache_dir, name) {
+        eprintln!("ERROR: failed to remove cache entry for plugin '{name}': {}", e.cause);
+    }
+
+    println!("removed plugin '{name}'");
 
     Ok(())
 }
 
-unsafe fn load_symbol<F>(module: *mut libc::c_void, name: &std::ffi::CStr) -> Result<F> {
-    // SAFETY: this is safe because `module` is returned by `dlopen` and the
-    // symbol name is a valid nul-terminated string pointer
-    let symbol = libc::dlsym(module, name.as_ptr());
-    if symbol.is_null() {
-        return Err(make_dlerror_error());
+fn do_command_unload_all_plugins(plugins: &mut Vec<plugin_sdk::Handle>) -> Result<()> {
+    if plugins.is_empty() {
+        println!("no plugins loaded yet");
+        return Ok(());
     }
 
-    // SAFETY: a cast from `*mut void` is only safe if the dynamic library exports the symbol with the correct type
-    let func_ptr: F = std::mem::transmute_copy(&symbol);
-    Ok(func_ptr)
+    for handle in plugins.drain(..) {
+        dispatch_plugin_event(handle, plugin_sdk::PluginEvent::Shutdown);
+
+        let Some(plugin) = loaded_plugins().remove(handle) else {
+            continue;
+        };
+
+        let Some(mut module) = plugin.module else {
+            println!("removed plugin {:?} (was not loaded)", plugin.path);
+            continue;
+        };
+
+        // See the matching comment in `remove_plugin`: drop the command
+        // handlers before unmapping the code their `Drop` impls call into.
+        module.custom_commands.clear();
+
+        // SAFETY: `module.dl_handle` was returned by a successful `dlopen`
+        let rc = unsafe { libc::dlclose(module.dl_handle) };
+        if rc != 0 {
+            eprintln!("ERROR: failed to unload plugin {:?}: {}", plugin.path, make_dlerror_error().cause);
+            continue;
+        }
+
+        println!("unloaded plugin {:?}", plugin.path);
+    }
+
+    Ok(())
 }
 
-fn do_command_unload_all_plugins(plugin_state: &mut PluginState) -> Result<()> {
-    if plugin_state.path.is_none() {
+fn do_command_reset_plugin(plugins: &[plugin_sdk::Handle]) {
+    if plugins.is_empty() {
         println!("no plugins loaded yet");
+        return;
+    }
+
+    for &handle in plugins {
+        dispatch_plugin_event(handle, plugin_sdk::PluginEvent::Reset);
+    }
+}
+
+/// Registers a plugin not already known to the registry, inserting it as a
+/// new [`LoadedPlugin`]. If the on-disk cache has a record for `name` whose
+/// mtime/size still match `path`, the plugin is registered without being
+/// `dlopen`ed at all -- `ensure_plugin_loaded` will load it the first time
+/// one of its commands is actually invoked, or the first time an ambient
+/// event like `CommandInput`/`PromptTick` is dispatched to it, whichever
+/// comes first. Otherwise it's loaded eagerly right away so we can learn
+/// (and cache) its command names.
+fn register_plugin(name: String, path: &std::path::Path, cache_dir: &std::path::Path) -> Result<plugin_sdk::Handle> {
+    let (mtime, size) = stat_plugin(path)?;
+    let path = path_to_cstring(path)?;
+
+    if let Some(cache) = read_plugin_cache(cache_dir, &name) {
+        if cache.mtime == mtime && cache.size == size {
+            return Ok(loaded_plugins().insert(LoadedPlugin {
+                name,
+                path,
+                cached_commands: cache.commands,
+                module: None,
+            }));
+        }
+    }
+
+    let handle = loaded_plugins().insert(LoadedPlugin {
+        name,
+        path,
+        cached_commands: Vec::new(),
+        module: None,
+    });
+
+    ensure_plugin_loaded(handle, cache_dir)?;
+
+    Ok(handle)
+}
+
+/// `dlopen`s the plugin behind `handle` if it isn't already loaded, then
+/// dispatches `PluginEvent::Load` so it can register its commands. Refreshes
+/// `cached_commands` and the on-disk cache entry from whatever the plugin
+/// actually registered, so a cache that was missing, stale, or simply never
+/// written (as well as a plugin that registers different commands than last
+/// time) ends up with the right answer either way.
+fn ensure_plugin_loaded(handle: plugin_sdk::Handle, cache_dir: &std::path::Path) -> Result<()> {
+    let already_loaded = loaded_plugins().get_mut(handle).is_some_and(|plugin| plugin.module.is_some());
+    if already_loaded {
         return Ok(());
     }
 
+    let Some(plugin) = loaded_plugins().get_mut(handle) else {
+        return Ok(());
+    };
+    let path = plugin.path.clone();
+
     // SAFETY: this is safe because file path is a valid nul-terminated string pointer
-    let rc = unsafe { libc::dlclose(plugin_state.module) };
-    if rc != 0 {
+    let dl_handle = unsafe { libc::dlopen(path.as_ptr(), libc::RTLD_LOCAL | libc::RTLD_NOW) };
+    if dl_handle.is_null() {
         return Err(make_dlerror_error());
     }
 
-    let plugin_path = plugin_state.path.take().expect("just made sure it's there");
-    println!("unloaded plugin {plugin_path:?}");
-    plugin_state.module = std::ptr::null_mut();
-    plugin_state.custom_prompt_function = None;
-    plugin_state.custom_commands.clear();
+    // SAFETY: assumming the plugin defines the symbol with the correct signature
+    let on_event: plugin_sdk::PluginOnEventFn = unsafe { load_symbol(dl_handle, c"ffi_plugin_on_event")? };
+
+    let plugin = loaded_plugins().get_mut(handle).expect("handle was just looked up successfully");
+    plugin.module = Some(LoadedModule { dl_handle, on_event: Some(on_event), custom_commands: Default::default() });
+
+    dispatch_plugin_event(handle, plugin_sdk::PluginEvent::Load);
+
+    let plugin = loaded_plugins().get_mut(handle).expect("handle was just looked up successfully");
+    let commands: Vec<String> = plugin.module.as_ref().expect("just set above").custom_commands.keys().cloned().collect();
+    plugin.cached_commands = commands.clone();
+
+    let (mtime, size) = stat_plugin(std::path::Path::new(plugin.path.to_str().unwrap_or_default()))
+        .unwrap_or((0, 0));
+    if let Err(e) = write_plugin_cache(cache_dir, &plugin.name, mtime, size, &commands) {
+        eprintln!("ERROR: failed to write cache entry for plugin '{}': {}", plugin.name, e.cause);
+    }
 
     Ok(())
 }
 
+unsafe fn load_symbol<F>(module: *mut libc::c_void, name: &std::ffi::CStr) -> Result<F> {
+    // SAFETY: this is safe because `module` is returned by `dlopen` and the
+    // symbol name is a valid nul-terminated string pointer
+    let symbol = libc::dlsym(module, name.as_ptr());
+    if symbol.is_null() {
+        return Err(make_dlerror_error());
+    }
+
+    // SAFETY: a cast from `*mut void` is only safe if the dynamic library exports the symbol with the correct type
+    let func_ptr: F = std::mem::transmute_copy(&symbol);
+    Ok(func_ptr)
+}
+
 fn make_dlerror_error() -> Error {
     // SAFETY: this call is always safe
     let error = unsafe { libc::dlerror() };
@@ -214,25 +498,345 @@ fn make_dlerror_error() -> Error {
     return Error::message(message);
 }
 
+fn plugin_name(path: &std::path::Path) -> Result<String> {
+    path.file_stem()
+        .and_then(|s| s.to_str())
+        .map(str::to_owned)
+        .ok_or_else(|| Error::message(format!("couldn't derive a plugin name from path {path:?}")))
+}
+
+fn path_to_cstring(path: &std::path::Path) -> Result<std::ffi::CString> {
+    let Some(s) = path.to_str() else {
+        return Err(Error::message(format!("plugin path {path:?} is not valid UTF-8")));
+    };
+
+    std::ffi::CString::new(s).map_err(Error::new)
+}
+
+fn stat_plugin(path: &std::path::Path) -> Result<(u64, u64)> {
+    let metadata = std::fs::metadata(path)?;
+    let mtime = metadata.modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    Ok((mtime, metadata.len()))
+}
+
 #[no_mangle]
-pub extern "C" fn ffi_register_command(context: *mut (), handler: plugin_sdk::CommandHandler) -> bool {
-    let plugin_state_ptr = context as *mut PluginState;
-    let plugin_state = unsafe { &mut *plugin_state_ptr };
+pub extern "C" fn ffi_register_command(context: plugin_sdk::Handle, handler: plugin_sdk::CommandHandler) -> i32 {
+    let Some(plugin) = loaded_plugins().get_mut(context) else {
+        println!("ERROR: tried to register a command against a context that is no longer loaded");
+        return plugin_sdk::REGISTER_COMMAND_INVALID_HANDLE;
+    };
+
+    let Some(module) = plugin.module.as_mut() else {
+        println!("ERROR: tried to register a command before the plugin's module was loaded");
+        return plugin_sdk::REGISTER_COMMAND_INVALID_HANDLE;
+    };
 
     let command = handler.name().to_owned();
-    let e = plugin_state.custom_commands.entry(command);
+    let e = module.custom_commands.entry(command);
     match e {
         std::collections::hash_map::Entry::Occupied(e) => {
             let command = e.key();
-            println!("ERROR: custom command '{command}' is already registerred");
-            return false;
+            println!("ERROR: custom command '{command}' is already registerred for plugin '{}'", plugin.name);
+            return plugin_sdk::REGISTER_COMMAND_ALREADY_REGISTERED;
         }
         std::collections::hash_map::Entry::Vacant(e) => {
             e.insert(handler);
         }
     }
 
-    return true;
+    return plugin_sdk::REGISTER_COMMAND_OK;
+}
+
+#[no_mangle]
+pub extern "C" fn ffi_lookup(context: plugin_sdk::Handle) -> bool {
+    loaded_plugins().contains(context)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// plugin config
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(serde::Deserialize)]
+struct PluginConfig {
+    #[serde(default = "default_plugins_dir")]
+    plugins_dir: std::path::PathBuf,
+    /// Where per-plugin command caches are kept (see the `plugin cache`
+    /// section below).
+    #[serde(default = "default_cache_dir")]
+    cache_dir: std::path::PathBuf,
+    /// If `true`, `whitelist` is the only source of truth for which plugins
+    /// to load and `blacklist` is ignored; otherwise every plugin is loaded
+    /// except the ones named in `blacklist`.
+    #[serde(default)]
+    as_whitelist: bool,
+    #[serde(default)]
+    blacklist: Vec<String>,
+    #[serde(default)]
+    whitelist: Vec<String>,
+    /// Fixes the load order (and therefore the prompt composition and
+    /// command-resolution order) for the plugins named here; any other
+    /// allowed plugin is loaded afterwards in whatever order the directory
+    /// scan found it.
+    #[serde(default)]
+    template: Vec<String>,
+    #[serde(default = "default_prompt_separator")]
+    prompt_separator: String,
+}
+
+impl Default for PluginConfig {
+    fn default() -> Self {
+        Self {
+            plugins_dir: default_plugins_dir(),
+            cache_dir: default_cache_dir(),
+            as_whitelist: false,
+            blacklist: Vec::new(),
+            whitelist: Vec::new(),
+            template: Vec::new(),
+            prompt_separator: default_prompt_separator(),
+        }
+    }
+}
+
+fn default_plugins_dir() -> std::path::PathBuf {
+    std::path::PathBuf::from("plugins")
+}
+
+fn default_cache_dir() -> std::path::PathBuf {
+    std::path::PathBuf::from("plugin-cache")
+}
+
+fn default_prompt_separator() -> String {
+    " | ".to_owned()
+}
+
+fn load_plugin_config(path: &str) -> Result<PluginConfig> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(PluginConfig::default()),
+        Err(e) => return Err(e.into()),
+    };
+
+    toml::from_str(&contents).map_err(Error::new)
+}
+
+fn plugin_allowed(config: &PluginConfig, name: &str) -> bool {
+    if config.as_whitelist {
+        config.whitelist.iter().any(|n| n == name)
+    } else {
+        !config.blacklist.iter().any(|n| n == name)
+    }
+}
+
+fn load_plugins_from_config(config: &PluginConfig) -> Result<Vec<plugin_sdk::Handle>> {
+    let entries = match std::fs::read_dir(&config.plugins_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut candidates = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let Ok(name) = plugin_name(&path) else {
+            continue;
+        };
+
+        if plugin_allowed(config, &name) {
+            candidates.push((name, path));
+        }
+    }
+
+    // `template` fixes an explicit order for the plugins named in it; ties
+    // (including everything not mentioned in `template`) keep the order the
+    // directory scan produced them in, since `sort_by_key` is stable.
+    candidates.sort_by_key(|(name, _)| {
+        config.template.iter().position(|templated| templated == name).unwrap_or(config.template.len())
+    });
+
+    let mut handles = Vec::with_capacity(candidates.len());
+    for (name, path) in candidates {
+        match register_plugin(name, &path, &config.cache_dir) {
+            Ok(handle) => handles.push(handle),
+            Err(e) => {
+                eprintln!("ERROR: failed to load plugin: {}", e.cause);
+                eprintln!("Backtrace:");
+                eprintln!("{}", e.backtrace);
+            }
+        }
+    }
+
+    Ok(handles)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// plugin cache
+////////////////////////////////////////////////////////////////////////////////
+//
+// Resolving every plugin's command set by `dlopen`+`dlsym` on each launch is
+// wasteful once there can be many of them, so each plugin's record -- the
+// mtime/size we last saw its file at, and the command names it registered --
+// is persisted to its own file under `cache_dir`, named after the plugin.
+// Keeping one file per plugin (rather than one big cache file) means adding,
+// updating, or removing a single plugin's record never touches anybody
+// else's, and a corrupt record only costs us that one plugin's cache.
+//
+// Each file is a flat binary encoding: mtime (u64 LE), size (u64 LE), command
+// count (u32 LE), then each command name as a u32 LE length followed by its
+// UTF-8 bytes -- the same length-prefixed shape `FfiSafeString` uses to pass
+// strings across the FFI boundary.
+
+struct PluginCacheEntry {
+    mtime: u64,
+    size: u64,
+    commands: Vec<String>,
+}
+
+fn plugin_cache_path(cache_dir: &std::path::Path, name: &str) -> std::path::PathBuf {
+    cache_dir.join(format!("{name}.cache"))
+}
+
+fn read_plugin_cache(cache_dir: &std::path::Path, name: &str) -> Option<PluginCacheEntry> {
+    let bytes = std::fs::read(plugin_cache_path(cache_dir, name)).ok()?;
+    match parse_plugin_cache(&bytes) {
+        Some(entry) => Some(entry),
+        None => {
+            eprintln!("ERROR: cache entry for plugin '{name}' is corrupt, ignoring it");
+            None
+        }
+    }
+}
+
+fn parse_plugin_cache(bytes: &[u8]) -> Option<PluginCacheEntry> {
+    let mut cursor = bytes;
+    let mtime = take_u64(&mut cursor)?;
+    let size = take_u64(&mut cursor)?;
+    let count = take_u32(&mut cursor)?;
+
+    // `count` is untrusted data read straight off disk, so don't use it to
+    // pre-size an allocation -- a corrupt or truncated cache file could claim
+    // an absurd count and abort the process via the allocator's OOM handler
+    // before we ever get to validate a single record.
+    let mut commands = Vec::new();
+    for _ in 0..count {
+        let len = take_u32(&mut cursor)? as usize;
+        if cursor.len() < len {
+            return None;
+        }
+        let (name_bytes, rest) = cursor.split_at(len);
+        commands.push(std::str::from_utf8(name_bytes).ok()?.to_owned());
+        cursor = rest;
+    }
+
+    Some(PluginCacheEntry { mtime, size, commands })
+}
+
+fn take_u64(cursor: &mut &[u8]) -> Option<u64> {
+    if cursor.len() < 8 {
+        return None;
+    }
+    let (bytes, rest) = cursor.split_at(8);
+    *cursor = rest;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn take_u32(cursor: &mut &[u8]) -> Option<u32> {
+    if cursor.len() < 4 {
+        return None;
+    }
+    let (bytes, rest) = cursor.split_at(4);
+    *cursor = rest;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn write_plugin_cache(cache_dir: &std::path::Path, name: &str, mtime: u64, size: u64, commands: &[String]) -> Result<()> {
+    std::fs::create_dir_all(cache_dir)?;
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&mtime.to_le_bytes());
+    bytes.extend_from_slice(&size.to_le_bytes());
+    bytes.extend_from_slice(&(commands.len() as u32).to_le_bytes());
+    for command in commands {
+        bytes.extend_from_slice(&(command.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(command.as_bytes());
+    }
+
+    std::fs::write(plugin_cache_path(cache_dir, name), bytes)?;
+    Ok(())
+}
+
+fn remove_plugin_cache(cache_dir: &std::path::Path, name: &str) -> Result<()> {
+    match std::fs::remove_file(plugin_cache_path(cache_dir, name)) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod plugin_cache_tests {
+    use super::*;
+
+    fn encode(mtime: u64, size: u64, commands: &[&str]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&mtime.to_le_bytes());
+        bytes.extend_from_slice(&size.to_le_bytes());
+        bytes.extend_from_slice(&(commands.len() as u32).to_le_bytes());
+        for command in commands {
+            bytes.extend_from_slice(&(command.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(command.as_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn round_trips_a_well_formed_entry() {
+        let bytes = encode(123, 456, &["count", "reset-counter"]);
+
+        let entry = parse_plugin_cache(&bytes).unwrap();
+
+        assert_eq!(entry.mtime, 123);
+        assert_eq!(entry.size, 456);
+        assert_eq!(entry.commands, vec!["count".to_owned(), "reset-counter".to_owned()]);
+    }
+
+    #[test]
+    fn rejects_a_truncated_header() {
+        let bytes = encode(1, 2, &["count"]);
+        assert!(parse_plugin_cache(&bytes[..4]).is_none());
+    }
+
+    #[test]
+    fn rejects_a_count_that_overruns_the_buffer() {
+        // Claims two commands but the buffer only has room to back one --
+        // this is the case that must not be taken as license to
+        // `Vec::with_capacity(count)` before validating it.
+        let mut bytes = encode(1, 2, &["count"]);
+        let count_offset = 16;
+        bytes[count_offset..count_offset + 4].copy_from_slice(&2u32.to_le_bytes());
+
+        assert!(parse_plugin_cache(&bytes).is_none());
+    }
+
+    #[test]
+    fn rejects_a_command_length_that_overruns_the_buffer() {
+        let mut bytes = encode(1, 2, &["count"]);
+        bytes.truncate(bytes.len() - 1);
+
+        assert!(parse_plugin_cache(&bytes).is_none());
+    }
+
+    #[test]
+    fn rejects_non_utf8_command_bytes() {
+        let mut bytes = encode(1, 2, &[]);
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        bytes.extend_from_slice(&[0xff, 0xfe]);
+
+        assert!(parse_plugin_cache(&bytes).is_none());
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
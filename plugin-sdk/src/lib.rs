@@ -1,10 +1,61 @@
-pub type CustomPromptFn = extern "C" fn();
-pub type PluginOnLoadFn = extern "C" fn(*mut ());
+/// A message the host sends a plugin about something that happened, in place
+/// of the old polling-style load hook plus synchronous command callbacks. A
+/// plugin implements a single `ffi_plugin_on_event` symbol and switches on
+/// the tag instead of exporting one symbol per occasion.
+#[repr(C)]
+pub enum PluginEvent {
+    /// The plugin was just `dlopen`ed for the first time; this is where it
+    /// should call `ffi_register_command` for each command it provides.
+    Load,
+    /// The plugin was asked to load again while still loaded (e.g. the same
+    /// path was passed to `load-plugin` twice without an unload in between).
+    /// The module stays mapped, so a stateful plugin can choose to persist
+    /// or clear itself here rather than being silently treated as fresh.
+    Reload,
+    /// The host was asked to reset plugin state without unloading it.
+    Reset,
+    /// A line of input the host is about to dispatch as a command.
+    CommandInput { line: FfiSafeStr },
+    /// The host is about to draw the command line prompt. A successful
+    /// return carries this plugin's contribution to the prompt in the
+    /// `message` field (see [`FfiError::success_with_message`]); the host
+    /// joins every loaded plugin's contribution with a separator.
+    PromptTick,
+    /// The plugin is about to be `dlclose`d.
+    Shutdown,
+}
+
+/// `extern "C-unwind"` rather than plain `extern "C"`: unlike `CommandHandler`,
+/// whose `trampoline` absorbs panics inside SDK-owned code before they'd ever
+/// need to cross an `extern "C"` frame, this symbol *is* the plugin author's
+/// own code. A panic inside it has to unwind out through this boundary to
+/// reach the host's `catch_unwind` in `dispatch_plugin_event`; across a plain
+/// `extern "C"` frame that unwind is UB and aborts the process on the spot.
+pub type PluginOnEventFn = extern "C-unwind" fn(context: Handle, event: *const PluginEvent) -> FfiError;
 
 extern "C" {
-    pub fn ffi_register_command(context: *mut (), callback: CommandHandler) -> bool;
+    /// Registers `callback` against the plugin context identified by
+    /// `context`. Returns one of the `REGISTER_COMMAND_*` codes rather than a
+    /// plain `bool` so the plugin can tell "a command with this name is
+    /// already registered" apart from "this context handle is stale" (e.g.
+    /// the plugin was unloaded out from under it).
+    pub fn ffi_register_command(context: Handle, callback: CommandHandler) -> i32;
+
+    /// Checks whether `context` still refers to a live plugin context,
+    /// without touching it. Lets a plugin guard a deferred callback against
+    /// a handle that has outlived its generation instead of risking a
+    /// lookup against freed/reused state.
+    pub fn ffi_lookup(context: Handle) -> bool;
 }
 
+/// `ffi_register_command` succeeded.
+pub const REGISTER_COMMAND_OK: i32 = 0;
+/// A command with that name was already registered for this context.
+pub const REGISTER_COMMAND_ALREADY_REGISTERED: i32 = 1;
+/// `context` does not refer to a live plugin context (stale generation or
+/// out-of-range index).
+pub const REGISTER_COMMAND_INVALID_HANDLE: i32 = 2;
+
 #[repr(C)]
 pub struct CommandHandler {
     pub name: FfiSafeString,
@@ -39,16 +90,21 @@ impl CommandHandler {
         unsafe { self.name.as_str() }
     }
 
-    pub fn call(&self, args: &[&str]) -> bool {
+    pub fn call(&self, args: &[&str]) -> Result<(), FfiError> {
         let mut args_copy = Vec::with_capacity(args.len());
         for arg in args {
             args_copy.push(FfiSafeStr::new(arg));
         }
 
-        (self.closure_fn)(self, FfiSafeSlice::new(&args_copy))
+        let error = (self.closure_fn)(self, FfiSafeSlice::new(&args_copy));
+        if error.code == FfiError::CODE_SUCCESS {
+            Ok(())
+        } else {
+            Err(error)
+        }
     }
 
-    extern "C" fn trampoline<F>(handler: *const Self, args: FfiSafeSlice<FfiSafeStr>) -> bool
+    extern "C" fn trampoline<F>(handler: *const Self, args: FfiSafeSlice<FfiSafeStr>) -> FfiError
     where
         F: Fn(&[&str]) -> Result<(), Box<dyn std::error::Error>>,
     {
@@ -70,13 +126,30 @@ impl CommandHandler {
             closure = unsafe { &*closure_ptr };
         }
 
-        let res = closure(&args_copy);
+        // Plugin code runs on the other side of an `extern "C"` boundary, so a
+        // panic must never be allowed to unwind through it (that's UB). We
+        // catch it here and turn it into an ordinary failure result instead.
+        // Note this only covers unwinding panics: a plugin that aborts still
+        // takes the whole process down with it.
+        let caught = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| closure(&args_copy)));
+
+        let res = match caught {
+            Ok(res) => res,
+            Err(payload) => {
+                // Just the message -- the host already wraps whatever we
+                // return in its own `Error`, which captures and prints its
+                // own backtrace, so baking one in here too would print two
+                // interleaved "Backtrace:" sections.
+                let message = panic_payload_message(&*payload);
+                return FfiError::new(FfiError::CODE_PANIC, message);
+            }
+        };
+
         if let Err(e) = res {
-            println!("ERROR: {e}");
-            return false;
+            return FfiError::new(FfiError::CODE_PLUGIN_ERROR, e.to_string());
         }
 
-        return true;
+        FfiError::success()
     }
 
     /// # Safety
@@ -101,7 +174,88 @@ impl Drop for CommandHandler {
 type CommandCallbackFn = extern "C" fn(
     handler: *const CommandHandler,
     args: FfiSafeSlice<FfiSafeStr>,
-) -> bool;
+) -> FfiError;
+
+/// The outcome of a call across the FFI boundary: `code == CODE_SUCCESS` means
+/// the call succeeded, any other code is a failure described by `message`.
+///
+/// Negative codes are reserved for errors raised by the SDK itself (a caught
+/// panic, an allocation failure, ...); plugins should use positive codes for
+/// their own error categories, starting from [`FfiError::CODE_PLUGIN_ERROR`].
+/// This mirrors the ExternError pattern: the struct owns its `message` and
+/// whichever side allocated it is responsible for freeing it.
+#[repr(C)]
+pub struct FfiError {
+    pub code: i32,
+    pub message: FfiSafeString,
+}
+
+impl FfiError {
+    pub const CODE_SUCCESS: i32 = 0;
+    /// The callee panicked and the panic was caught at the FFI boundary.
+    pub const CODE_PANIC: i32 = -1;
+    /// The SDK failed to allocate memory needed to service the call.
+    pub const CODE_ALLOC_FAILURE: i32 = -2;
+    /// Generic plugin-defined error; plugins that need finer-grained
+    /// categories should pick their own codes starting from here.
+    pub const CODE_PLUGIN_ERROR: i32 = 1;
+
+    pub fn new(code: i32, message: impl Into<String>) -> Self {
+        assert_ne!(code, Self::CODE_SUCCESS, "use FfiError::success() for the success case");
+        Self { code, message: FfiSafeString::new(message.into()) }
+    }
+
+    pub fn success() -> Self {
+        Self { code: Self::CODE_SUCCESS, message: FfiSafeString::new(String::new()) }
+    }
+
+    /// A success result that also carries a return value in `message` (e.g.
+    /// a [`PluginEvent::PromptTick`] handler's prompt contribution).
+    pub fn success_with_message(message: impl Into<String>) -> Self {
+        Self { code: Self::CODE_SUCCESS, message: FfiSafeString::new(message.into()) }
+    }
+
+    pub fn message(&self) -> &str {
+        // SAFETY: data in `message` is owned by `self`
+        unsafe { self.message.as_str() }
+    }
+}
+
+impl std::fmt::Debug for FfiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FfiError")
+            .field("code", &self.code)
+            .field("message", &self.message())
+            .finish()
+    }
+}
+
+impl std::fmt::Display for FfiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Just the message: every call site that reports an `FfiError`
+        // already prefixes its own "(code N)" (see `dispatch_plugin_event`
+        // and `do_plugin_command` in host-program), so doing it here too
+        // would print the code twice.
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for FfiError {}
+
+/// Turns a caught panic payload into a human-readable message.
+///
+/// Used on both sides of the FFI boundary: by [`CommandHandler::trampoline`]
+/// and by the host when it calls into a plugin-provided function directly
+/// (e.g. dispatching a [`PluginEvent`]).
+pub fn panic_payload_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        (*s).to_owned()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "plugin panicked with a non-string payload".to_owned()
+    }
+}
 
 #[repr(C)]
 pub struct FfiSafeString {
@@ -162,7 +316,7 @@ pub struct FfiSafeStr {
 }
 
 impl FfiSafeStr {
-    fn new(s: &str) -> FfiSafeStr {
+    pub fn new(s: &str) -> FfiSafeStr {
         let data = s.as_ptr();
         let len = s.len();
         Self { data, len }
@@ -170,7 +324,7 @@ impl FfiSafeStr {
 
     /// # Safety
     /// The pointer must be valid for the lifetime of `self`.
-    unsafe fn as_str(&self) -> &str {
+    pub unsafe fn as_str(&self) -> &str {
         let bytes = std::slice::from_raw_parts(self.data, self.len);
         // SAFETY: data is guaranteed to be utf8 by construction
         std::str::from_utf8_unchecked(bytes)
@@ -197,3 +351,145 @@ impl<T> FfiSafeSlice<T> {
         std::slice::from_raw_parts(self.data, self.len)
     }
 }
+
+////////////////////////////////////////////////////////////////////////////////
+// handle map
+////////////////////////////////////////////////////////////////////////////////
+
+/// An opaque reference to a value owned by a [`HandleMap`], handed to plugin
+/// code instead of a raw pointer. Packs a slot index together with a
+/// generation counter, so a handle outlived by a [`HandleMap::remove`] call
+/// can be detected and rejected instead of aliasing whatever ends up reusing
+/// that slot.
+pub type Handle = u64;
+
+const HANDLE_GENERATION_SHIFT: u32 = 32;
+
+fn pack_handle(index: u32, generation: u32) -> Handle {
+    ((generation as u64) << HANDLE_GENERATION_SHIFT) | index as u64
+}
+
+fn unpack_handle(handle: Handle) -> (u32, u32) {
+    let index = handle as u32;
+    let generation = (handle >> HANDLE_GENERATION_SHIFT) as u32;
+    (index, generation)
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+/// A generational slab: values are inserted once and referenced afterwards
+/// only through the opaque [`Handle`] that `insert` returns. Removing a value
+/// bumps its slot's generation, so any handle issued before the removal no
+/// longer matches and `get_mut`/`remove` report it as absent rather than
+/// handing out access to whatever later reuses that slot.
+pub struct HandleMap<T> {
+    slots: Vec<Slot<T>>,
+    free_indices: Vec<u32>,
+}
+
+impl<T> HandleMap<T> {
+    pub fn new() -> Self {
+        Self { slots: Vec::new(), free_indices: Vec::new() }
+    }
+
+    pub fn insert(&mut self, value: T) -> Handle {
+        if let Some(index) = self.free_indices.pop() {
+            let slot = &mut self.slots[index as usize];
+            slot.value = Some(value);
+            return pack_handle(index, slot.generation);
+        }
+
+        let index = self.slots.len() as u32;
+        self.slots.push(Slot { generation: 0, value: Some(value) });
+        pack_handle(index, 0)
+    }
+
+    pub fn get_mut(&mut self, handle: Handle) -> Option<&mut T> {
+        let (index, generation) = unpack_handle(handle);
+        let slot = self.slots.get_mut(index as usize)?;
+        if slot.generation != generation {
+            return None;
+        }
+
+        slot.value.as_mut()
+    }
+
+    pub fn remove(&mut self, handle: Handle) -> Option<T> {
+        let (index, generation) = unpack_handle(handle);
+        let slot = self.slots.get_mut(index as usize)?;
+        if slot.generation != generation {
+            return None;
+        }
+
+        let value = slot.value.take();
+        // Any handle issued against this slot so far must never be honored
+        // again, even once the slot is reused by a future `insert`.
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free_indices.push(index);
+        value
+    }
+
+    pub fn contains(&self, handle: Handle) -> bool {
+        let (index, generation) = unpack_handle(handle);
+        let Some(slot) = self.slots.get(index as usize) else {
+            return false;
+        };
+
+        slot.generation == generation && slot.value.is_some()
+    }
+}
+
+impl<T> Default for HandleMap<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod handle_map_tests {
+    use super::HandleMap;
+
+    #[test]
+    fn get_mut_and_remove_round_trip() {
+        let mut map = HandleMap::new();
+        let handle = map.insert(42);
+
+        assert_eq!(map.get_mut(handle), Some(&mut 42));
+        assert_eq!(map.remove(handle), Some(42));
+        assert_eq!(map.get_mut(handle), None);
+    }
+
+    #[test]
+    fn stale_handle_is_rejected_after_slot_reuse() {
+        let mut map = HandleMap::new();
+        let first = map.insert("first");
+        map.remove(first).unwrap();
+
+        let second = map.insert("second");
+
+        assert_eq!(map.get_mut(first), None);
+        assert!(!map.contains(first));
+        assert_eq!(map.get_mut(second), Some(&mut "second"));
+    }
+
+    #[test]
+    fn removing_twice_is_a_no_op() {
+        let mut map = HandleMap::new();
+        let handle = map.insert(1);
+
+        assert_eq!(map.remove(handle), Some(1));
+        assert_eq!(map.remove(handle), None);
+    }
+
+    #[test]
+    fn unknown_handle_is_rejected() {
+        let mut map: HandleMap<i32> = HandleMap::new();
+        let bogus = super::pack_handle(0, 0);
+
+        assert_eq!(map.get_mut(bogus), None);
+        assert!(!map.contains(bogus));
+    }
+}
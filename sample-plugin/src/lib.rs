@@ -1,28 +1,39 @@
-use std::io::Write;
-
 static mut COUNTER: usize = 0;
 
 #[no_mangle]
-pub extern "C" fn ffi_custom_prompt() {
-    let counter = unsafe { COUNTER };
-    print!("{counter} $ ");
-    _ = std::io::stdout().flush();
+pub extern "C-unwind" fn ffi_plugin_on_event(context: plugin_sdk::Handle, event: *const plugin_sdk::PluginEvent) -> plugin_sdk::FfiError {
+    // SAFETY: the host guarantees `event` is valid for the duration of this call
+    let event = unsafe { &*event };
+
+    match event {
+        plugin_sdk::PluginEvent::Load => register_commands(context),
+        plugin_sdk::PluginEvent::Reload => {
+            // We were never unloaded, so our statics (and our registered
+            // commands) are already intact; nothing to do.
+        }
+        plugin_sdk::PluginEvent::Reset => unsafe { COUNTER = 0 },
+        plugin_sdk::PluginEvent::PromptTick => {
+            let counter = unsafe { COUNTER };
+            unsafe { COUNTER += 1 };
+            return plugin_sdk::FfiError::success_with_message(format!("{counter} $"));
+        }
+        plugin_sdk::PluginEvent::CommandInput { .. } => {}
+        plugin_sdk::PluginEvent::Shutdown => {}
+    }
 
-    unsafe { COUNTER += 1 };
+    plugin_sdk::FfiError::success()
 }
 
-const _CHECK1: plugin_sdk::CustomPromptFn = ffi_custom_prompt;
-
-#[no_mangle]
-pub extern "C" fn ffi_plugin_on_load(context: *mut ()) {
+const _CHECK: plugin_sdk::PluginOnEventFn = ffi_plugin_on_event;
 
+fn register_commands(context: plugin_sdk::Handle) {
     let handler = plugin_sdk::CommandHandler::new("reset-counter".into(), |_| {
         unsafe { COUNTER = 0 };
         Ok(())
     });
-    let ok = unsafe { plugin_sdk::ffi_register_command(context, handler) };
-    if !ok {
-        println!("couldn't register command");
+    let rc = unsafe { plugin_sdk::ffi_register_command(context, handler) };
+    if rc != plugin_sdk::REGISTER_COMMAND_OK {
+        println!("couldn't register command (code {rc})");
     }
 
     let handler = plugin_sdk::CommandHandler::new("count".into(), |args| {
@@ -31,5 +42,3 @@ pub extern "C" fn ffi_plugin_on_load(context: *mut ()) {
     });
     unsafe { plugin_sdk::ffi_register_command(context, handler) };
 }
-
-const _CHECK2: plugin_sdk::PluginOnLoadFn = ffi_plugin_on_load;